@@ -2,18 +2,22 @@ use anyhow::{bail, Context, Result};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 mod symbols;
 
 const USAGE_STRING: &str = "Usage:
-  cargo xtask bundle <package> [--release] [--target <triple>]
-  cargo xtask bundle -p <package1> -p <package2> ... [--release] [--target <triple>]";
+  cargo xtask bundle <package> [--release] [--target <triple>] [--universal]
+  cargo xtask bundle -p <package1> -p <package2> ... [--release] [--target <triple>] [--universal]";
 
 /// The base birectory for the bundler's output.
 const BUNDLE_HOME: &str = "target/bundled";
 
+/// The targets that make up a universal macOS bundle. When `--universal` is passed we build each of
+/// these and merge the resulting libraries into a single fat Mach-O with `lipo`.
+const MACOS_UNIVERSAL_TARGETS: [&str; 2] = ["x86_64-apple-darwin", "aarch64-apple-darwin"];
+
 /// Any additional configuration that might be useful for creating plugin bundles, stored as
 /// `bundler.toml` alongside the workspace's main `Cargo.toml` file.
 type BundlerConfig = HashMap<String, PackageConfig>;
@@ -21,21 +25,62 @@ type BundlerConfig = HashMap<String, PackageConfig>;
 #[derive(Debug, Clone, Deserialize)]
 struct PackageConfig {
     name: Option<String>,
+    /// The macOS code signing identity. When set, generated macOS bundles are signed with
+    /// `codesign` as the final bundling step.
+    signing_identity: Option<String>,
+    /// Overrides the reverse-DNS bundle identifier embedded in the macOS `Info.plist`. Defaults to
+    /// `com.nih-plug.<name>` for VST3 bundles and `com.nih-plug.<name>.clap` for CLAP bundles,
+    /// where `<name>` is the bundle name.
+    identifier: Option<String>,
+    /// Overrides the bundle version embedded in the macOS `Info.plist`. Defaults to `1.0.0`.
+    version: Option<String>,
+    /// Overrides the four-character bundle signature used in `PkgInfo` and the `Info.plist`.
+    /// Defaults to `????`.
+    signature: Option<String>,
+}
+
+/// A minimal view of `.cargo/config.toml`, used to discover the `build.target` default that cargo
+/// builds for when no `--target` is passed.
+#[derive(Debug, Deserialize)]
+struct CargoConfig {
+    build: Option<CargoConfigBuild>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CargoConfigBuild {
+    target: Option<CargoConfigTarget>,
+}
+
+/// Cargo's `build.target` accepts either a single triple or an array of triples, so we mirror both
+/// forms here.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum CargoConfigTarget {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+/// The architecture of a compilation target. This is the first segment of a target triple, and it
+/// determines the VST3 architecture subfolder on Linux and Windows. The library name depends only
+/// on the OS.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arch {
+    X86,
+    X86_64,
+    Aarch64,
 }
 
 /// The target we're generating a plugin for. This can be either the native target or a cross
 /// compilation target, so to reduce redundancy when determining the correct bundle paths we'll use
-/// an enum for this.
-///
-/// TODO: Right now we don't consider ARM targets at all
+/// an enum for this. The OS is paired with the [Arch] rather than baked into the variant name so we
+/// don't need a combinatorial explosion of variants as we add architectures.
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum CompilationTarget {
-    Linux64,
-    Linux32,
-    Mac64,
-    Windows64,
-    Windows32,
+    Linux(Arch),
+    MacOS(Arch),
+    Windows(Arch),
 }
 
 fn main() -> Result<()> {
@@ -87,17 +132,20 @@ fn main() -> Result<()> {
 
 // TODO: The macOS version has not been tested
 fn bundle(package: &str, args: &[String]) -> Result<()> {
-    let bundle_name = match load_bundler_config()?.and_then(|c| c.get(package).cloned()) {
-        Some(PackageConfig { name: Some(name) }) => name,
-        _ => package.to_string(),
-    };
+    let package_config = load_bundler_config()?.and_then(|mut config| config.remove(package));
+    let bundle_name = package_config
+        .as_ref()
+        .and_then(|config| config.name.clone())
+        .unwrap_or_else(|| package.to_string());
 
     let mut is_release_build = false;
     let mut cross_compile_target: Option<String> = None;
+    let mut universal = false;
     for arg_idx in (0..args.len()).rev() {
         let arg = &args[arg_idx];
         match arg.as_str() {
             "--release" => is_release_build = true,
+            "--universal" => universal = true,
             "--target" => {
                 // When cross compiling we should generate the correct bundle type
                 cross_compile_target = Some(
@@ -117,29 +165,92 @@ fn bundle(package: &str, args: &[String]) -> Result<()> {
         }
     }
 
-    let status = Command::new("cargo")
-        .arg("build")
-        .arg("-p")
-        .arg(package)
-        .args(args)
-        .status()
-        .context(format!("Could not call cargo to build {package}"))?;
-    if !status.success() {
-        bail!("Could not build {}", package);
+    // When no explicit `--target` is passed, cargo may still cross-compile because of a default
+    // target set through `CARGO_BUILD_TARGET` or `.cargo/config.toml`. We mirror that here so the
+    // detected compilation target and output directory match what cargo actually produced instead
+    // of silently falling back to the host triple.
+    if cross_compile_target.is_none() {
+        cross_compile_target = default_build_target()?;
     }
 
-    let compilation_target = compilation_target(cross_compile_target.as_deref())?;
-    let lib_path = Path::new(target_base(cross_compile_target.as_deref())?)
-        .join(if is_release_build { "release" } else { "debug" })
-        .join(library_basename(package, compilation_target));
-    if !lib_path.exists() {
-        bail!("Could not find built library at '{}'", lib_path.display());
+    // `--universal` is our own flag for producing a fat macOS binary; cargo doesn't understand it,
+    // so we strip it out before forwarding the remaining arguments to `cargo build`. When building
+    // a universal bundle we also drop any explicit `--target`, since we append our own per-slice
+    // `--target` below and cargo rejects the flag being passed twice.
+    let mut cargo_args: Vec<&str> = Vec::with_capacity(args.len());
+    let mut args_iter = args.iter().map(String::as_str);
+    while let Some(arg) = args_iter.next() {
+        match arg {
+            "--universal" => (),
+            "--target" if universal => {
+                // Also skip the triple that follows the flag.
+                args_iter.next();
+            }
+            arg if universal && arg.starts_with("--target=") => (),
+            arg => cargo_args.push(arg),
+        }
     }
+    let profile_dir = if is_release_build { "release" } else { "debug" };
+
+    // A universal macOS bundle is built by compiling each architecture separately and merging the
+    // resulting libraries with `lipo` further down. Everything else is a single native or
+    // cross-compiled build.
+    let (compilation_target, lib_paths) = if universal {
+        let mut lib_paths = Vec::new();
+        for triple in MACOS_UNIVERSAL_TARGETS {
+            let status = Command::new("cargo")
+                .arg("build")
+                .arg("-p")
+                .arg(package)
+                .args(&cargo_args)
+                .arg("--target")
+                .arg(triple)
+                .status()
+                .context(format!("Could not call cargo to build {package}"))?;
+            if !status.success() {
+                bail!("Could not build {}", package);
+            }
 
-    // We'll detect the pugin formats supported by the plugin binary and create bundled accordingly
-    // TODO: Support VST2 and CLAP here
-    let bundle_vst3 = symbols::exported(&lib_path, "GetPluginFactory")
-        .with_context(|| format!("Could not parse '{}'", lib_path.display()))?;
+            let lib_path = Path::new(&target_base(Some(triple))?)
+                .join(profile_dir)
+                .join(library_basename(package, compilation_target(Some(triple))?));
+            if !lib_path.exists() {
+                bail!("Could not find built library at '{}'", lib_path.display());
+            }
+            lib_paths.push(lib_path);
+        }
+
+        (CompilationTarget::MacOS(Arch::Aarch64), lib_paths)
+    } else {
+        let status = Command::new("cargo")
+            .arg("build")
+            .arg("-p")
+            .arg(package)
+            .args(&cargo_args)
+            .status()
+            .context(format!("Could not call cargo to build {package}"))?;
+        if !status.success() {
+            bail!("Could not build {}", package);
+        }
+
+        let compilation_target = compilation_target(cross_compile_target.as_deref())?;
+        let lib_path = Path::new(&target_base(cross_compile_target.as_deref())?)
+            .join(profile_dir)
+            .join(library_basename(package, compilation_target));
+        if !lib_path.exists() {
+            bail!("Could not find built library at '{}'", lib_path.display());
+        }
+
+        (compilation_target, vec![lib_path])
+    };
+
+    // We'll detect the plugin formats supported by the plugin binary from its exported symbols and
+    // create a bundle for each one. A single build can export both factories.
+    // TODO: Support VST2 here
+    let bundle_vst3 = symbols::exported(&lib_paths[0], "GetPluginFactory")
+        .with_context(|| format!("Could not parse '{}'", lib_paths[0].display()))?;
+    let bundle_clap = symbols::exported(&lib_paths[0], "clap_entry")
+        .with_context(|| format!("Could not parse '{}'", lib_paths[0].display()))?;
 
     eprintln!();
     if bundle_vst3 {
@@ -155,13 +266,41 @@ fn bundle(package: &str, args: &[String]) -> Result<()> {
 
         fs::create_dir_all(vst3_lib_path.parent().unwrap())
             .context("Could not create bundle directory")?;
-        reflink::reflink_or_copy(&lib_path, &vst3_lib_path)
-            .context("Could not copy library to bundle")?;
+        copy_or_combine_libraries(&lib_paths, &vst3_lib_path)?;
 
-        maybe_create_macos_vst3_bundle(package, compilation_target)?;
+        maybe_create_macos_vst3_bundle(&bundle_name, compilation_target, package_config.as_ref())?;
+        maybe_codesign(vst3_bundle_home, compilation_target, package_config.as_ref())?;
 
         eprintln!("Created a VST3 bundle at '{}'", vst3_bundle_home.display());
-    } else {
+    }
+    if bundle_clap {
+        let clap_lib_path =
+            Path::new(BUNDLE_HOME).join(clap_bundle_library_name(&bundle_name, compilation_target));
+
+        fs::create_dir_all(clap_lib_path.parent().unwrap())
+            .context("Could not create bundle directory")?;
+        copy_or_combine_libraries(&lib_paths, &clap_lib_path)?;
+
+        maybe_create_macos_clap_bundle(&bundle_name, compilation_target, package_config.as_ref())?;
+
+        // On Linux and Windows the `.clap` file is the bundle, while on macOS the bundle is the
+        // `.clap` directory three levels up from the library (`<name>.clap/Contents/MacOS/<name>`),
+        // mirroring the VST3 computation above.
+        let clap_bundle_home = match compilation_target {
+            CompilationTarget::MacOS(_) => clap_lib_path
+                .parent()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .parent()
+                .unwrap(),
+            _ => clap_lib_path.as_path(),
+        };
+        maybe_codesign(clap_bundle_home, compilation_target, package_config.as_ref())?;
+
+        eprintln!("Created a CLAP bundle at '{}'", clap_bundle_home.display());
+    }
+    if !bundle_vst3 && !bundle_clap {
         eprintln!("Not creating any plugin bundles because the package does not export any plugins")
     }
 
@@ -198,47 +337,119 @@ fn load_bundler_config() -> Result<Option<BundlerConfig>> {
     Ok(Some(result))
 }
 
+/// Decompose a target triple into a [CompilationTarget]. Rather than matching entire triples we
+/// split the triple on `-` and classify the architecture prefix and the OS segment the same way
+/// rustc and cc do (`target_arch`, `target_vendor = "apple"`, `target_os`). The vendor segment is
+/// only used to recognize Apple platforms, where the OS is spelled `darwin`.
+fn parse_target(triple: &str) -> Result<CompilationTarget> {
+    let mut segments = triple.split('-');
+    let arch = match segments.next() {
+        Some("aarch64") => Arch::Aarch64,
+        Some("x86_64") => Arch::X86_64,
+        Some("i686") | Some("i586") => Arch::X86,
+        _ => bail!("Unhandled cross-compilation target: {}", triple),
+    };
+
+    // The remaining segments hold the vendor and OS. We only need the OS to pick the bundle layout;
+    // the ABI suffix is irrelevant here since the MSVC and GNU toolchains produce identically-named
+    // `.dll` outputs, so `*-pc-windows-msvc` and `*-pc-windows-gnu` map to the same bundle.
+    let rest: Vec<&str> = segments.collect();
+    if rest.iter().any(|&s| s == "linux") {
+        Ok(CompilationTarget::Linux(arch))
+    } else if rest.iter().any(|&s| s == "apple" || s == "darwin") {
+        Ok(CompilationTarget::MacOS(arch))
+    } else if rest.iter().any(|&s| s == "windows") {
+        Ok(CompilationTarget::Windows(arch))
+    } else {
+        bail!("Unhandled cross-compilation target: {}", triple)
+    }
+}
+
+/// Determine the target triple cargo will build for when no `--target` is passed on the command
+/// line. Cargo honors the `CARGO_BUILD_TARGET` environment variable and the `build.target` key in
+/// `.cargo/config.toml`, in that order of precedence, so we consult the same sources to keep the
+/// bundle layout in sync with the compiled artifacts.
+fn default_build_target() -> Result<Option<String>> {
+    if let Ok(target) = std::env::var("CARGO_BUILD_TARGET") {
+        if !target.is_empty() {
+            return Ok(Some(target));
+        }
+    }
+
+    // We're already in the project root. Cargo also reads the extensionless `.cargo/config`.
+    for config_path in [".cargo/config.toml", ".cargo/config"] {
+        let config_path = Path::new(config_path);
+        if !config_path.exists() {
+            continue;
+        }
+
+        let config: CargoConfig = toml::from_str(
+            &fs::read_to_string(config_path)
+                .with_context(|| format!("Could not read '{}'", config_path.display()))?,
+        )
+        .with_context(|| format!("Could not parse '{}'", config_path.display()))?;
+        if let Some(target) = config.build.and_then(|build| build.target) {
+            let triple = match target {
+                CargoConfigTarget::Single(triple) => triple,
+                // Cargo builds for the first triple in the array; we match that so the bundle
+                // layout lines up with the produced artifacts.
+                CargoConfigTarget::Multiple(triples) => triples.into_iter().next().with_context(
+                    || format!("`build.target` in '{}' is an empty array", config_path.display()),
+                )?,
+            };
+            return Ok(Some(triple));
+        }
+    }
+
+    Ok(None)
+}
+
 /// The target we're compiling for. This is used to determine the paths and options for creating
 /// plugin bundles.
 fn compilation_target(cross_compile_target: Option<&str>) -> Result<CompilationTarget> {
     match cross_compile_target {
-        Some("x86_64-unknown-linux-gnu") => Ok(CompilationTarget::Linux64),
-        Some("x86_64-apple-darwin") => Ok(CompilationTarget::Mac64),
-        Some("x86_64-pc-windows-gnu") => Ok(CompilationTarget::Windows64),
-        Some(target) => bail!("Unhandled cross-compilation target: {}", target),
+        Some(triple) => parse_target(triple),
         None => {
             #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-            return Ok(CompilationTarget::Linux64);
+            return Ok(CompilationTarget::Linux(Arch::X86_64));
             #[cfg(all(target_os = "linux", target_arch = "x86"))]
-            return Ok(CompilationTarget::Linux32);
+            return Ok(CompilationTarget::Linux(Arch::X86));
+            #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+            return Ok(CompilationTarget::Linux(Arch::Aarch64));
             #[cfg(all(target_os = "macos", target_arch = "x86_64"))]
-            return Ok(CompilationTarget::Mac64);
+            return Ok(CompilationTarget::MacOS(Arch::X86_64));
+            #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+            return Ok(CompilationTarget::MacOS(Arch::Aarch64));
             #[cfg(all(target_os = "windows", target_arch = "x86_64"))]
-            return Ok(CompilationTarget::Windows64);
+            return Ok(CompilationTarget::Windows(Arch::X86_64));
             #[cfg(all(target_os = "windows", target_arch = "x86"))]
-            return Ok(CompilationTarget::Windows32);
+            return Ok(CompilationTarget::Windows(Arch::X86));
+            #[cfg(all(target_os = "windows", target_arch = "aarch64"))]
+            return Ok(CompilationTarget::Windows(Arch::Aarch64));
         }
     }
 }
 
 /// The base directory for the compiled binaries. This does not use [CompilationTarget] as we need
 /// to be able to differentiate between native and cross-compilation.
-fn target_base(cross_compile_target: Option<&str>) -> Result<&'static str> {
+fn target_base(cross_compile_target: Option<&str>) -> Result<String> {
     match cross_compile_target {
-        Some("x86_64-unknown-linux-gnu") => Ok("target/x86_64-unknown-linux-gnu"),
-        Some("x86_64-pc-windows-gnu") => Ok("target/x86_64-pc-windows-gnu"),
-        Some("x86_64-apple-darwin") => Ok("target/x86_64-apple-darwin"),
-        Some(target) => bail!("Unhandled cross-compilation target: {}", target),
-        None => Ok("target"),
+        // Validate the triple first so we fail early on unsupported targets instead of pointing at
+        // a directory that will never exist.
+        Some(triple) => {
+            compilation_target(Some(triple))?;
+            Ok(format!("target/{triple}"))
+        }
+        None => Ok(String::from("target")),
     }
 }
 
 /// The file name of the compiled library for a `cdylib` crate.
 fn library_basename(package: &str, target: CompilationTarget) -> String {
     match target {
-        CompilationTarget::Linux64 | CompilationTarget::Linux32 => format!("lib{package}.so"),
-        CompilationTarget::Mac64 => format!("lib{package}.dylib"),
-        CompilationTarget::Windows64 | CompilationTarget::Windows32 => format!("{package}.dll"),
+        CompilationTarget::Linux(_) => format!("lib{package}.so"),
+        CompilationTarget::MacOS(_) => format!("lib{package}.dylib"),
+        CompilationTarget::Windows(_) => format!("{package}.dll"),
     }
 }
 
@@ -248,31 +459,127 @@ fn library_basename(package: &str, target: CompilationTarget) -> String {
 /// directory.
 fn vst3_bundle_library_name(package: &str, target: CompilationTarget) -> String {
     match target {
-        CompilationTarget::Linux64 => format!("{package}.vst3/Contents/x86_64-linux/{package}.so"),
-        CompilationTarget::Linux32 => format!("{package}.vst3/Contents/i386-linux/{package}.so"),
-        CompilationTarget::Mac64 => format!("{package}.vst3/Contents/MacOS/{package}"),
-        CompilationTarget::Windows64 => {
+        CompilationTarget::Linux(Arch::X86) => {
+            format!("{package}.vst3/Contents/i386-linux/{package}.so")
+        }
+        CompilationTarget::Linux(Arch::X86_64) => {
+            format!("{package}.vst3/Contents/x86_64-linux/{package}.so")
+        }
+        CompilationTarget::Linux(Arch::Aarch64) => {
+            format!("{package}.vst3/Contents/aarch64-linux/{package}.so")
+        }
+        CompilationTarget::MacOS(_) => format!("{package}.vst3/Contents/MacOS/{package}"),
+        CompilationTarget::Windows(Arch::X86) => {
+            format!("{package}.vst3/Contents/x86-win/{package}.vst3")
+        }
+        CompilationTarget::Windows(Arch::X86_64) => {
             format!("{package}.vst3/Contents/x86_64-win/{package}.vst3")
         }
-        CompilationTarget::Windows32 => format!("{package}.vst3/Contents/x86-win/{package}.vst3"),
+        CompilationTarget::Windows(Arch::Aarch64) => {
+            format!("{package}.vst3/Contents/arm64-win/{package}.vst3")
+        }
+    }
+}
+
+/// Place the compiled library (or libraries) at `output`. A single library is reflinked or copied,
+/// while multiple single-architecture libraries are merged into one fat Mach-O with `lipo` so a
+/// universal macOS bundle can contain both the `x86_64` and `arm64` slices.
+fn copy_or_combine_libraries(lib_paths: &[PathBuf], output: &Path) -> Result<()> {
+    match lib_paths {
+        [lib_path] => {
+            reflink::reflink_or_copy(lib_path, output)
+                .context("Could not copy library to bundle")?;
+        }
+        _ => {
+            let status = Command::new("lipo")
+                .arg("-create")
+                .arg("-output")
+                .arg(output)
+                .args(lib_paths)
+                .status()
+                .context("Could not call lipo to create a universal library")?;
+            if !status.success() {
+                bail!("Could not create a universal library with lipo");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The path to the library file inside of a CLAP bundle. On Linux and Windows the `.clap` file is
+/// simply the renamed library, while on macOS it is a bundle directory like the VST3 one.
+fn clap_bundle_library_name(package: &str, target: CompilationTarget) -> String {
+    match target {
+        CompilationTarget::MacOS(_) => format!("{package}.clap/Contents/MacOS/{package}"),
+        _ => format!("{package}.clap"),
     }
 }
 
 /// If compiling for macOS, create all of the bundl-y stuff Steinberg and Apple require you to have.
-fn maybe_create_macos_vst3_bundle(package: &str, target: CompilationTarget) -> Result<()> {
-    if target != CompilationTarget::Mac64 {
+fn maybe_create_macos_vst3_bundle(
+    package: &str,
+    target: CompilationTarget,
+    config: Option<&PackageConfig>,
+) -> Result<()> {
+    if !matches!(target, CompilationTarget::MacOS(_)) {
         return Ok(());
     }
 
-    // TODO: May want to add bundler.toml fields for the identifier, version and signature at some
-    //       point.
+    create_macos_bundle_metadata(
+        &format!("{BUNDLE_HOME}/{package}.vst3"),
+        package,
+        &format!("com.nih-plug.{package}"),
+        config,
+    )
+}
+
+/// Same as [maybe_create_macos_vst3_bundle], but for CLAP bundles. CLAP uses the same macOS bundle
+/// layout as VST3, only with a `.clap` extension and a CLAP-specific bundle identifier.
+fn maybe_create_macos_clap_bundle(
+    package: &str,
+    target: CompilationTarget,
+    config: Option<&PackageConfig>,
+) -> Result<()> {
+    if !matches!(target, CompilationTarget::MacOS(_)) {
+        return Ok(());
+    }
+
+    create_macos_bundle_metadata(
+        &format!("{BUNDLE_HOME}/{package}.clap"),
+        package,
+        &format!("com.nih-plug.{package}.clap"),
+        config,
+    )
+}
+
+/// Write the `PkgInfo` and `Info.plist` files macOS requires inside a plugin bundle's `Contents`
+/// directory. `bundle_dir` is the path to the `.vst3`/`.clap` directory and `default_identifier` is
+/// the reverse-DNS bundle identifier to embed when `bundler.toml` doesn't override it. The
+/// identifier, version, and signature can all be overridden through the package's [PackageConfig].
+fn create_macos_bundle_metadata(
+    bundle_dir: &str,
+    package: &str,
+    default_identifier: &str,
+    config: Option<&PackageConfig>,
+) -> Result<()> {
+    let identifier = config
+        .and_then(|config| config.identifier.as_deref())
+        .unwrap_or(default_identifier);
+    let version = config
+        .and_then(|config| config.version.as_deref())
+        .unwrap_or("1.0.0");
+    let signature = config
+        .and_then(|config| config.signature.as_deref())
+        .unwrap_or("????");
+
     fs::write(
-        format!("{}/{}.vst3/Contents/PkgInfo", BUNDLE_HOME, package),
-        "BNDL????",
+        format!("{bundle_dir}/Contents/PkgInfo"),
+        format!("BNDL{signature}"),
     )
     .context("Could not create PkgInfo file")?;
     fs::write(
-        format!("{}/{}.vst3/Contents/Info.plist", BUNDLE_HOME, package),
+        format!("{bundle_dir}/Contents/Info.plist"),
         format!(r#"<?xml version="1.0" encoding="UTF-8"?>
 
 <!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
@@ -283,7 +590,7 @@ fn maybe_create_macos_vst3_bundle(package: &str, target: CompilationTarget) -> R
     <key>CFBundleIconFile</key>
     <string></string>
     <key>CFBundleIdentifier</key>
-    <string>com.nih-plug.{package}</string>
+    <string>{identifier}</string>
     <key>CFBundleName</key>
     <string>{package}</string>
     <key>CFBundleDisplayName</key>
@@ -291,11 +598,11 @@ fn maybe_create_macos_vst3_bundle(package: &str, target: CompilationTarget) -> R
     <key>CFBundlePackageType</key>
     <string>BNDL</string>
     <key>CFBundleSignature</key>
-    <string>????</string>
+    <string>{signature}</string>
     <key>CFBundleShortVersionString</key>
-    <string>1.0.0</string>
+    <string>{version}</string>
     <key>CFBundleVersion</key>
-    <string>1.0.0</string>
+    <string>{version}</string>
     <key>NSHumanReadableCopyright</key>
     <string></string>
     <key>NSHighResolutionCapable</key>
@@ -308,3 +615,35 @@ fn maybe_create_macos_vst3_bundle(package: &str, target: CompilationTarget) -> R
 
     Ok(())
 }
+
+/// Sign an assembled macOS bundle with `codesign` when the package configures a signing identity.
+/// This is a no-op on other platforms and when no identity is set, and it has to run only after the
+/// bundle tree (library plus `Contents`) is fully in place.
+fn maybe_codesign(
+    bundle_dir: &Path,
+    target: CompilationTarget,
+    config: Option<&PackageConfig>,
+) -> Result<()> {
+    if !matches!(target, CompilationTarget::MacOS(_)) {
+        return Ok(());
+    }
+
+    let identity = match config.and_then(|config| config.signing_identity.as_deref()) {
+        Some(identity) => identity,
+        None => return Ok(()),
+    };
+
+    let status = Command::new("codesign")
+        .arg("--force")
+        .arg("--sign")
+        .arg(identity)
+        .arg("--deep")
+        .arg(bundle_dir)
+        .status()
+        .context("Could not call codesign to sign the bundle")?;
+    if !status.success() {
+        bail!("Could not sign '{}'", bundle_dir.display());
+    }
+
+    Ok(())
+}